@@ -0,0 +1,112 @@
+//! Shared conditional-request validation for cache providers that store `(etag, last_modified)`
+//! entries, so `IF_NONE_MATCH`/`IF_MODIFIED_SINCE` handling is only implemented once
+
+use http::{
+    header::{IF_MODIFIED_SINCE, IF_NONE_MATCH},
+    HeaderMap, HeaderValue,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::etag_builder::etag_matches;
+
+/// Outcome of validating a request's conditional headers against a cached entry
+pub enum ValidationResult {
+    Hit(HeaderMap),
+    Miss,
+}
+
+/// Checks `headers` against a cached `(cache_etag, last_modified)` entry, preferring
+/// `IF_NONE_MATCH` entity-tag matching and only falling back to `IF_MODIFIED_SINCE` when the
+/// client sent no `IF_NONE_MATCH` at all, per RFC 7232
+///
+/// `set_response_headers` is called to populate the `Hit` header map with `ETAG`,
+/// `CACHE_CONTROL` and `LAST_MODIFIED` in whatever way the calling provider does so
+pub fn validate_cached_entry(
+    headers: &HeaderMap,
+    cache_etag: &str,
+    last_modified: SystemTime,
+    set_response_headers: impl Fn(&mut HeaderMap, HeaderValue, SystemTime),
+) -> ValidationResult {
+    if headers.contains_key(IF_NONE_MATCH) {
+        for etag in headers.get_all(IF_NONE_MATCH) {
+            let Ok(etag_str) = etag.to_str() else {
+                continue;
+            };
+            if etag_matches(etag_str, cache_etag) {
+                let mut header_map = HeaderMap::new();
+                set_response_headers(&mut header_map, etag.clone(), last_modified);
+                return ValidationResult::Hit(header_map);
+            }
+        }
+        return ValidationResult::Miss;
+    }
+
+    if let Some(if_modified_since) = headers.get(IF_MODIFIED_SINCE) {
+        let is_fresh = if_modified_since
+            .to_str()
+            .ok()
+            .and_then(|s| if_modified_since_is_fresh(s, last_modified));
+        if is_fresh == Some(true) {
+            let mut header_map = HeaderMap::new();
+            // unwrap-safety: cache_etag was itself produced from a validated HeaderValue
+            let etag_val = HeaderValue::from_str(cache_etag).unwrap();
+            set_response_headers(&mut header_map, etag_val, last_modified);
+            return ValidationResult::Hit(header_map);
+        }
+    }
+
+    ValidationResult::Miss
+}
+
+/// Returns `Some(true)` if `if_modified_since_value` parses as an HTTP-date (IMF-fixdate,
+/// RFC 850, or asctime, per RFC 7231 section 7.1.1.1) and the cached entry is not newer, both
+/// truncated to whole seconds since HTTP dates have 1-second resolution; `Some(false)` if it
+/// parses but the entry is newer; `None` if the header value isn't a valid HTTP-date
+pub fn if_modified_since_is_fresh(if_modified_since_value: &str, last_modified: SystemTime) -> Option<bool> {
+    let requested = httpdate::parse_http_date(if_modified_since_value).ok()?;
+    let requested_secs = requested.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let cached_secs = last_modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    Some(cached_secs <= requested_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_imf_fixdate_with_literal_gmt() {
+        // Real clients send IMF-fixdate with a literal `GMT`, not a numeric RFC 2822 offset
+        let last_modified = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(
+            if_modified_since_is_fresh("Sun, 06 Nov 1994 08:49:37 GMT", last_modified),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn stale_entry_is_not_fresh() {
+        let last_modified = UNIX_EPOCH + Duration::from_secs(784_111_777 + 60);
+        assert_eq!(
+            if_modified_since_is_fresh("Sun, 06 Nov 1994 08:49:37 GMT", last_modified),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn sub_second_difference_truncates_to_fresh() {
+        let last_modified = UNIX_EPOCH + Duration::from_millis(784_111_777_500);
+        assert_eq!(
+            if_modified_since_is_fresh("Sun, 06 Nov 1994 08:49:37 GMT", last_modified),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn invalid_header_value_is_none() {
+        assert_eq!(
+            if_modified_since_is_fresh("not a date", SystemTime::now()),
+            None
+        );
+    }
+}
@@ -1,20 +1,24 @@
 //! An in-memory [`CacheProvider`] backed by a single `ConstLru`
 
+use bytes::{Buf, BufMut, BytesMut};
 use const_lru::ConstLru;
 use http::{
-    header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH, LAST_MODIFIED},
+    header::{CACHE_CONTROL, ETAG, LAST_MODIFIED},
     HeaderMap, HeaderValue,
 };
 use http_body::Body;
 use http_body_util::BodyExt;
 use num_traits::{PrimInt, Unsigned};
-use std::{alloc::alloc, alloc::Layout, error::Error, ptr::addr_of_mut, time::SystemTime};
+use std::{
+    alloc::alloc, alloc::Layout, error::Error, mem::size_of, ptr::addr_of_mut, time::SystemTime,
+};
 use time::{format_description::well_known::Rfc2822, OffsetDateTime};
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::PollSender;
 
 use crate::{
-    base64_blake3_body_etag::base64_blake3_body_etag,
+    conditional_validation::{validate_cached_entry, ValidationResult},
+    etag_builder::{Base64Blake3EtagBuilder, EtagBuilder},
     simple_etag_cache_key::{calc_simple_etag_cache_key, SimpleEtagCacheKey},
     CacheGetResponse, CacheGetResponseResult, CacheProvider,
 };
@@ -44,12 +48,36 @@ pub type ReqTup<ReqBody, ResBody> = (
 pub enum ConstLruProviderReq<ReqBody, ResBody> {
     Get(http::Request<ReqBody>),
     Put(ConstLruProviderCacheKey, http::Response<ResBody>),
+    Stats,
 }
 
 #[derive(Debug)]
 pub enum ConstLruProviderRes<ReqBody> {
     Get(CacheGetResponse<ReqBody, ConstLruProviderCacheKey>),
     Put(http::Response<ConstLruProviderTResBody>),
+    Stats(ConstLruProviderStats),
+}
+
+/// Fixed per-entry bookkeeping overhead assumed when accounting for an entry's heap footprint
+/// under a byte budget (the key slot plus the value tuple's `SystemTime` inside the `ConstLru`
+/// backing array)
+const ENTRY_FIXED_OVERHEAD_BYTES: usize = size_of::<ConstLruProviderCacheKey>() + size_of::<SystemTime>();
+
+/// Heap footprint attributed to a single cache entry under the byte budget: the etag `String`'s
+/// allocated capacity plus [`ENTRY_FIXED_OVERHEAD_BYTES`]. A free function so it's unit-testable
+/// without standing up a whole [`ConstLruProvider`]
+fn entry_heap_bytes(etag_capacity: usize) -> usize {
+    etag_capacity + ENTRY_FIXED_OVERHEAD_BYTES
+}
+
+/// Snapshot of a [`ConstLruProvider`]'s memory usage and hit/miss counters, returned by
+/// [`ConstLruProviderHandle::stats`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstLruProviderStats {
+    pub entries: usize,
+    pub total_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
 }
 
 /// A basic in-memory ConstLru-backed cache provider.
@@ -60,10 +88,83 @@ pub enum ConstLruProviderRes<ReqBody> {
 ///
 /// Also stores the `SystemTime` of when the cache entry was created, which serves as the response's
 /// last-modified header value
+///
+/// When built with [`ConstLruProviderBuilder::max_bytes`], entries are additionally evicted
+/// LRU-first in `on_put_request` to keep the tracked heap footprint under `max_bytes`, on top of
+/// the existing `CAP` entry-count bound
+///
+/// The etag emitted (and later compared against `IF_NONE_MATCH`) is computed
+/// by the configurable [`EtagBuilder`]; see [`ConstLruProviderBuilder::etag_builder`]
 pub struct ConstLruProvider<ReqBody, ResBody: Body, const CAP: usize, I: PrimInt + Unsigned = usize>
 {
     const_lru: ConstLru<ConstLruProviderCacheKey, (String, SystemTime), CAP, I>,
     req_rx: mpsc::Receiver<ReqTup<ReqBody, ResBody>>,
+    max_bytes: Option<usize>,
+    total_bytes: usize,
+    hits: u64,
+    misses: u64,
+    etag_builder: Box<dyn EtagBuilder>,
+    max_cacheable_body_bytes: Option<usize>,
+}
+
+/// Builder for [`ConstLruProvider`], so options like a byte budget, a custom [`EtagBuilder`],
+/// and an oversized-body cap can be combined instead of being stuck with one fixed
+/// constructor per option
+#[derive(Default)]
+pub struct ConstLruProviderBuilder {
+    max_bytes: Option<usize>,
+    etag_builder: Option<Box<dyn EtagBuilder>>,
+    max_cacheable_body_bytes: Option<usize>,
+}
+
+impl ConstLruProviderBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Additionally evicts LRU entries in `on_put_request` so the cumulative heap footprint of
+    /// cached entries stays under `max_bytes`
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Computes and compares etags using `etag_builder` instead of the default strong
+    /// base64-blake3 tag
+    pub fn etag_builder(mut self, etag_builder: Box<dyn EtagBuilder>) -> Self {
+        self.etag_builder = Some(etag_builder);
+        self
+    }
+
+    /// Response bodies larger than `max_cacheable_body_bytes` are skipped entirely:
+    /// `on_put_request` streams them back through unmodified, without an ETag, instead of
+    /// caching them
+    pub fn max_cacheable_body_bytes(mut self, max_cacheable_body_bytes: usize) -> Self {
+        self.max_cacheable_body_bytes = Some(max_cacheable_body_bytes);
+        self
+    }
+
+    /// Allocates and creates a ConstLruProvider on the heap with the configured options and
+    /// returns the [`CacheProvider`] handle to it. See [`ConstLruProvider::init`] for details
+    pub fn init<ReqBody, ResBody, const CAP: usize, I>(
+        self,
+        req_buffer: usize,
+    ) -> ConstLruProviderHandle<ReqBody, ResBody>
+    where
+        ReqBody: Send + 'static,
+        ResBody: Send + Body + 'static,
+        I: PrimInt + Unsigned + Send + 'static,
+        <ResBody as Body>::Data: Send + Buf,
+        <ResBody as Body>::Error: Error + Send + Sync + 'static,
+    {
+        ConstLruProvider::<ReqBody, ResBody, CAP, I>::init_full(
+            req_buffer,
+            self.max_bytes,
+            self.etag_builder
+                .unwrap_or_else(|| Box::new(Base64Blake3EtagBuilder::strong())),
+            self.max_cacheable_body_bytes,
+        )
+    }
 }
 
 impl<
@@ -73,8 +174,8 @@ impl<
         I: PrimInt + Unsigned + Send + 'static,
     > ConstLruProvider<ReqBody, ResBody, CAP, I>
 where
-    <ResBody as Body>::Data: Send,
-    <ResBody as Body>::Error: Error + Send + Sync,
+    <ResBody as Body>::Data: Send + Buf,
+    <ResBody as Body>::Error: Error + Send + Sync + 'static,
 {
     /// Allocates and creates a ConstLruProvider on the heap and returns the [`CacheProvider`] handle to it.
     ///
@@ -84,9 +185,18 @@ where
     ///
     /// `req_buffer` is the size of the `mpsc::channel` connecting [`ConstLruProviderHandle`] to [`ConstLruProvider`]
     pub fn init(req_buffer: usize) -> ConstLruProviderHandle<ReqBody, ResBody> {
+        ConstLruProviderBuilder::new().init::<ReqBody, ResBody, CAP, I>(req_buffer)
+    }
+
+    fn init_full(
+        req_buffer: usize,
+        max_bytes: Option<usize>,
+        etag_builder: Box<dyn EtagBuilder>,
+        max_cacheable_body_bytes: Option<usize>,
+    ) -> ConstLruProviderHandle<ReqBody, ResBody> {
         let (req_tx, req_rx) = mpsc::channel(req_buffer);
 
-        let mut this = Self::boxed(req_rx);
+        let mut this = Self::boxed(req_rx, max_bytes, etag_builder, max_cacheable_body_bytes);
         tokio::spawn(async move { this.run().await });
 
         ConstLruProviderHandle {
@@ -94,7 +204,12 @@ where
         }
     }
 
-    fn boxed(req_rx: mpsc::Receiver<ReqTup<ReqBody, ResBody>>) -> Box<Self> {
+    fn boxed(
+        req_rx: mpsc::Receiver<ReqTup<ReqBody, ResBody>>,
+        max_bytes: Option<usize>,
+        etag_builder: Box<dyn EtagBuilder>,
+        max_cacheable_body_bytes: Option<usize>,
+    ) -> Box<Self> {
         // directly alloc so that a large ConstLru does not trigger stack overflow
         unsafe {
             let ptr = alloc(Layout::new::<Self>()) as *mut Self;
@@ -102,6 +217,12 @@ where
             ConstLru::init_at_alloc(const_lru_ptr);
             let req_rx_ptr = addr_of_mut!((*ptr).req_rx);
             req_rx_ptr.write(req_rx);
+            addr_of_mut!((*ptr).max_bytes).write(max_bytes);
+            addr_of_mut!((*ptr).total_bytes).write(0);
+            addr_of_mut!((*ptr).hits).write(0);
+            addr_of_mut!((*ptr).misses).write(0);
+            addr_of_mut!((*ptr).etag_builder).write(etag_builder);
+            addr_of_mut!((*ptr).max_cacheable_body_bytes).write(max_cacheable_body_bytes);
             Box::from_raw(ptr)
         }
     }
@@ -117,6 +238,7 @@ where
                     .on_put_request(key, resp)
                     .await
                     .map(ConstLruProviderRes::Put),
+                ConstLruProviderReq::Stats => Ok(ConstLruProviderRes::Stats(self.stats())),
             };
             // ignore error if resp_rx dropped
             let _ = resp_tx.send(res);
@@ -135,31 +257,34 @@ where
         let (cache_etag, last_modified) = match self.const_lru.get(&key) {
             Some(e) => e,
             None => {
+                self.misses += 1;
                 return Ok(CacheGetResponse {
                     req,
                     result: crate::CacheGetResponseResult::Miss(key),
-                })
+                });
             }
         };
-        let if_none_match_iter = req.headers().get_all(IF_NONE_MATCH);
-        for etag in if_none_match_iter {
-            let etag_str = match etag.to_str() {
-                Ok(s) => s,
-                Err(_) => continue,
-            };
-            if etag_str == cache_etag {
-                let mut header_map = HeaderMap::new();
-                Self::set_response_headers(&mut header_map, etag.clone(), *last_modified);
-                return Ok(CacheGetResponse {
+        match validate_cached_entry(
+            req.headers(),
+            cache_etag,
+            *last_modified,
+            Self::set_response_headers,
+        ) {
+            ValidationResult::Hit(header_map) => {
+                self.hits += 1;
+                Ok(CacheGetResponse {
                     req,
                     result: CacheGetResponseResult::Hit(header_map),
-                });
+                })
+            }
+            ValidationResult::Miss => {
+                self.misses += 1;
+                Ok(CacheGetResponse {
+                    req,
+                    result: CacheGetResponseResult::Miss(key),
+                })
             }
         }
-        Ok(CacheGetResponse {
-            req,
-            result: CacheGetResponseResult::Miss(key),
-        })
     }
 
     async fn on_put_request(
@@ -169,15 +294,48 @@ where
     ) -> Result<http::Response<ConstLruProviderTResBody>, ConstLruProviderError<ResBody::Error>>
     {
         let (mut parts, body) = resp.into_parts();
-        let body_bytes = BodyExt::collect(body)
-            .await
-            .map_err(ConstLruProviderError::ReadResBody)?
-            .to_bytes();
 
-        let etag = base64_blake3_body_etag(&body_bytes);
+        // Box so we can poll frame-by-frame regardless of whether ResBody is Unpin, tracking
+        // the accumulated size as frames arrive instead of buffering the whole body up front
+        let mut body = Box::pin(body);
+        let mut buf = BytesMut::new();
+        while let Some(frame) = body.as_mut().frame().await {
+            let frame = frame.map_err(ConstLruProviderError::ReadResBody)?;
+            if let Ok(mut data) = frame.into_data() {
+                buf.put(&mut data);
+                if let Some(max_cacheable_body_bytes) = self.max_cacheable_body_bytes {
+                    if buf.len() > max_cacheable_body_bytes {
+                        // Give up on caching and computing an etag for this entry right away:
+                        // stream what's already been buffered followed by the rest of the body,
+                        // instead of continuing to accumulate the whole thing in memory first
+                        let tres_body =
+                            ConstLruProviderTResBody::from_prefixed_body(buf.freeze(), body);
+                        return Ok(http::Response::from_parts(parts, tres_body));
+                    }
+                }
+            }
+        }
+        let body_bytes = buf.freeze();
+
+        let etag = self.etag_builder.build_etag(&body_bytes);
         // unwrap-safety: base64 should always be valid ascii
         let etag_str = etag.to_str().unwrap();
 
+        let old_size = self
+            .const_lru
+            .get(&key)
+            .map(|(etag, _)| entry_heap_bytes(etag.capacity()));
+
+        // a new key in a full ConstLru displaces its LRU entry; evict it here so its bytes
+        // leave total_bytes too
+        if old_size.is_none() && self.const_lru.is_full() {
+            if let Some((_, (evicted_etag, _))) = self.const_lru.pop_lru() {
+                self.total_bytes = self
+                    .total_bytes
+                    .saturating_sub(entry_heap_bytes(evicted_etag.capacity()));
+            }
+        }
+
         let curr_val = self
             .const_lru
             .entry(key)
@@ -190,11 +348,39 @@ where
         }
 
         let last_modified = curr_val.1;
+        let new_size = entry_heap_bytes(curr_val.0.capacity());
+        match old_size {
+            Some(old_size) => self.total_bytes = self.total_bytes.saturating_sub(old_size) + new_size,
+            None => self.total_bytes += new_size,
+        }
+
+        if let Some(max_bytes) = self.max_bytes {
+            while self.total_bytes > max_bytes {
+                match self.const_lru.pop_lru() {
+                    Some((_, (evicted_etag, _))) => {
+                        self.total_bytes = self
+                            .total_bytes
+                            .saturating_sub(entry_heap_bytes(evicted_etag.capacity()));
+                    }
+                    None => break,
+                }
+            }
+        }
+
         Self::set_response_headers(&mut parts.headers, etag, last_modified);
 
         Ok(http::Response::from_parts(parts, body_bytes.into()))
     }
 
+    fn stats(&self) -> ConstLruProviderStats {
+        ConstLruProviderStats {
+            entries: self.const_lru.len(),
+            total_bytes: self.total_bytes,
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
     fn set_response_headers(
         headers_mut: &mut HeaderMap,
         etag_val: HeaderValue,
@@ -238,3 +424,145 @@ where
     type Key = ConstLruProviderCacheKey;
     type TResBody = ConstLruProviderTResBody;
 }
+
+impl<ReqBody, ResBody: Body> ConstLruProviderHandle<ReqBody, ResBody> {
+    /// Queries the provider for its current entry count, tracked heap bytes, and cumulative
+    /// hit/miss counters
+    pub async fn stats(&mut self) -> ConstLruProviderStats {
+        use futures_util::SinkExt;
+
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.req_tx
+            .send((ConstLruProviderReq::Stats, resp_tx))
+            .await
+            .expect("ConstLruProvider actor task should not have been dropped");
+        match resp_rx
+            .await
+            .expect("ConstLruProvider actor task should not have been dropped")
+        {
+            Ok(ConstLruProviderRes::Stats(stats)) => stats,
+            Ok(_) => unreachable!("Stats request always returns ConstLruProviderRes::Stats"),
+            Err(_) => unreachable!("Stats request never errors"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header::IF_NONE_MATCH;
+    use http_body_util::Full;
+
+    fn test_provider(
+        max_bytes: Option<usize>,
+        max_cacheable_body_bytes: Option<usize>,
+    ) -> Box<ConstLruProvider<(), Full<bytes::Bytes>, 4, usize>> {
+        let (_req_tx, req_rx) = mpsc::channel(1);
+        ConstLruProvider::boxed(
+            req_rx,
+            max_bytes,
+            Box::new(Base64Blake3EtagBuilder::strong()),
+            max_cacheable_body_bytes,
+        )
+    }
+
+    fn get_request(path: &str) -> http::Request<()> {
+        http::Request::builder().uri(path).body(()).unwrap()
+    }
+
+    fn put_response(body: &'static [u8]) -> http::Response<Full<bytes::Bytes>> {
+        http::Response::new(Full::new(bytes::Bytes::from_static(body)))
+    }
+
+    #[test]
+    fn entry_heap_bytes_includes_fixed_overhead() {
+        assert_eq!(entry_heap_bytes(0), ENTRY_FIXED_OVERHEAD_BYTES);
+        assert_eq!(entry_heap_bytes(10), 10 + ENTRY_FIXED_OVERHEAD_BYTES);
+    }
+
+    #[tokio::test]
+    async fn put_then_matching_if_none_match_is_a_hit() {
+        let mut provider = test_provider(None, None);
+        let key = calc_simple_etag_cache_key(&get_request("/a"));
+        let put_res = provider
+            .on_put_request(key.clone(), put_response(b"hello"))
+            .await
+            .unwrap();
+        let etag = put_res.headers().get(ETAG).unwrap().clone();
+
+        let mut req = get_request("/a");
+        req.headers_mut().insert(IF_NONE_MATCH, etag);
+        let get_res = provider.on_get_request(req).unwrap();
+        assert!(matches!(get_res.result, CacheGetResponseResult::Hit(_)));
+
+        let stats = provider.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 0);
+    }
+
+    #[tokio::test]
+    async fn get_with_stale_if_none_match_is_a_miss() {
+        let mut provider = test_provider(None, None);
+        let key = calc_simple_etag_cache_key(&get_request("/a"));
+        provider
+            .on_put_request(key, put_response(b"hello"))
+            .await
+            .unwrap();
+
+        let mut req = get_request("/a");
+        req.headers_mut()
+            .insert(IF_NONE_MATCH, HeaderValue::from_static(r#""stale""#));
+        let get_res = provider.on_get_request(req).unwrap();
+        assert!(matches!(get_res.result, CacheGetResponseResult::Miss(_)));
+
+        let stats = provider.stats();
+        assert_eq!(stats.hits, 0);
+        assert_eq!(stats.misses, 1);
+    }
+
+    #[tokio::test]
+    async fn byte_budget_evicts_lru_entry_once_exceeded() {
+        // each entry costs ENTRY_FIXED_OVERHEAD_BYTES + the etag's allocated capacity; a budget
+        // this tight can only ever hold one entry at a time
+        let mut provider = test_provider(Some(ENTRY_FIXED_OVERHEAD_BYTES + 64), None);
+
+        let key_a = calc_simple_etag_cache_key(&get_request("/a"));
+        provider
+            .on_put_request(key_a.clone(), put_response(b"hello"))
+            .await
+            .unwrap();
+        assert_eq!(provider.stats().entries, 1);
+
+        let key_b = calc_simple_etag_cache_key(&get_request("/b"));
+        provider
+            .on_put_request(key_b, put_response(b"world"))
+            .await
+            .unwrap();
+
+        let stats = provider.stats();
+        // the older entry was evicted to stay under the budget
+        assert_eq!(stats.entries, 1);
+        assert!(stats.total_bytes <= ENTRY_FIXED_OVERHEAD_BYTES + 64);
+        assert!(provider.const_lru.get(&key_a).is_none());
+    }
+
+    #[tokio::test]
+    async fn oversized_body_is_streamed_through_without_caching() {
+        let mut provider = test_provider(None, Some(3));
+        let key = calc_simple_etag_cache_key(&get_request("/a"));
+        let res = provider
+            .on_put_request(key, put_response(b"way too long"))
+            .await
+            .unwrap();
+
+        // no etag computed, nothing cached
+        assert!(res.headers().get(ETAG).is_none());
+        assert_eq!(provider.stats().entries, 0);
+
+        let body_bytes = http_body_util::BodyExt::collect(res.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body_bytes, bytes::Bytes::from_static(b"way too long"));
+    }
+}
@@ -0,0 +1,122 @@
+//! Type-erased response body returned by [`super::ConstLruProvider`] (and shared by
+//! [`crate::disk_cache_provider::DiskCacheProvider`]): either a fully-buffered cached body, or a
+//! streaming passthrough for a response too large to cache
+
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame};
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full};
+use std::{
+    error::Error,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+pub struct ConstLruProviderTResBody(UnsyncBoxBody<Bytes, BoxError>);
+
+impl ConstLruProviderTResBody {
+    /// Wraps a fully-buffered, already-cached body
+    pub fn from_bytes(bytes: Bytes) -> Self {
+        Self(
+            Full::new(bytes)
+                .map_err(|never| match never {})
+                .boxed_unsync(),
+        )
+    }
+
+    /// Wraps a response that was given up on caching partway through buffering: `prefix` is the
+    /// bytes already read off `rest` before the oversized threshold was crossed, and `rest` is
+    /// the still-unconsumed remainder of the original body. Streams both through without
+    /// buffering the remainder, so an oversized body never pays the full-buffer memory cost
+    pub fn from_prefixed_body<B>(prefix: Bytes, rest: B) -> Self
+    where
+        B: Body + Send + 'static,
+        B::Data: Buf,
+        B::Error: Into<BoxError>,
+    {
+        let rest = rest
+            .map_frame(|frame| frame.map_data(|mut data| data.copy_to_bytes(data.remaining())))
+            .map_err(Into::into)
+            .boxed_unsync();
+        Self(PrefixedBody::new(prefix, rest).boxed_unsync())
+    }
+}
+
+impl From<Bytes> for ConstLruProviderTResBody {
+    fn from(bytes: Bytes) -> Self {
+        Self::from_bytes(bytes)
+    }
+}
+
+impl Body for ConstLruProviderTResBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        Pin::new(&mut self.0).poll_frame(cx)
+    }
+}
+
+/// Emits `prefix` as a single leading data frame, then delegates to `rest`.
+///
+/// Holding `rest` as an already-boxed [`UnsyncBoxBody`] (itself always `Unpin`) is what lets this
+/// struct stay `Unpin` regardless of the concrete body type the caller started with, so it can be
+/// polled without any `unsafe`
+struct PrefixedBody {
+    prefix: Option<Bytes>,
+    rest: UnsyncBoxBody<Bytes, BoxError>,
+}
+
+impl PrefixedBody {
+    fn new(prefix: Bytes, rest: UnsyncBoxBody<Bytes, BoxError>) -> Self {
+        let prefix = (!prefix.is_empty()).then_some(prefix);
+        Self { prefix, rest }
+    }
+}
+
+impl Body for PrefixedBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        if let Some(prefix) = self.prefix.take() {
+            return Poll::Ready(Some(Ok(Frame::data(prefix))));
+        }
+        Pin::new(&mut self.rest).poll_frame(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn from_bytes_yields_single_data_frame() {
+        let body = ConstLruProviderTResBody::from_bytes(Bytes::from_static(b"hello"));
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected, Bytes::from_static(b"hello"));
+    }
+
+    #[tokio::test]
+    async fn from_prefixed_body_streams_prefix_then_rest() {
+        let rest = Full::new(Bytes::from_static(b"world"));
+        let body = ConstLruProviderTResBody::from_prefixed_body(Bytes::from_static(b"hello"), rest);
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected, Bytes::from_static(b"helloworld"));
+    }
+
+    #[tokio::test]
+    async fn from_prefixed_body_with_empty_prefix_yields_only_rest() {
+        let rest = Full::new(Bytes::from_static(b"world"));
+        let body = ConstLruProviderTResBody::from_prefixed_body(Bytes::new(), rest);
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected, Bytes::from_static(b"world"));
+    }
+}
@@ -0,0 +1,27 @@
+use std::{error::Error, fmt};
+
+/// Errors that can arise from a [`super::DiskCacheProvider`].
+///
+/// Disk index I/O errors are deliberately not a variant here: a failed index write must not fail
+/// the response it was persisting alongside, so it's logged from `persist_entry` instead of being
+/// surfaced through this type
+#[derive(Debug)]
+pub enum DiskCacheProviderError<E> {
+    ReadResBody(E),
+}
+
+impl<E: fmt::Display> fmt::Display for DiskCacheProviderError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadResBody(e) => write!(f, "error reading response body: {e}"),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for DiskCacheProviderError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ReadResBody(e) => Some(e),
+        }
+    }
+}
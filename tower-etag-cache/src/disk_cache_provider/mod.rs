@@ -0,0 +1,516 @@
+//! A disk-backed [`CacheProvider`] that persists entries across process restarts,
+//! using an in-memory `ConstLru` as a hot-cache front
+
+use bytes::Buf;
+use const_lru::ConstLru;
+use http::{
+    header::{CACHE_CONTROL, ETAG, LAST_MODIFIED},
+    HeaderMap, HeaderValue,
+};
+use http_body::Body;
+use http_body_util::BodyExt;
+use num_traits::{PrimInt, Unsigned};
+use std::{
+    alloc::alloc,
+    alloc::Layout,
+    error::Error,
+    hash::{Hash, Hasher},
+    io::{BufRead, Write},
+    path::PathBuf,
+    ptr::addr_of_mut,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use time::{format_description::well_known::Rfc2822, OffsetDateTime};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::PollSender;
+
+use crate::{
+    conditional_validation::{validate_cached_entry, ValidationResult},
+    const_lru_provider::ConstLruProviderTResBody,
+    etag_builder::{Base64Blake3EtagBuilder, EtagBuilder},
+    simple_etag_cache_key::{calc_simple_etag_cache_key, SimpleEtagCacheKey},
+    CacheGetResponse, CacheGetResponseResult, CacheProvider,
+};
+
+mod err;
+
+pub use err::*;
+
+/// The hot-cache and on-disk index are keyed by this hash of the request's
+/// [`SimpleEtagCacheKey`] rather than the key itself, so the on-disk index is directly
+/// reversible: a line in the index *is* a `(key, etag, last_modified)` entry, with no need to
+/// reconstruct `SimpleEtagCacheKey` from anything
+pub type DiskCacheProviderCacheKey = u64;
+
+/// `DefaultHasher` is a non-cryptographic hash with no collision detection: two distinct
+/// `SimpleEtagCacheKey`s that happen to hash equal will silently serve each other's cached
+/// ETag/body. Low-probability at realistic `CAP` sizes, but a real tradeoff of keying the hot-cache
+/// and on-disk index by this hash rather than the original key
+fn hash_cache_key(key: &SimpleEtagCacheKey) -> DiskCacheProviderCacheKey {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Tuple containing the request to the provider and the oneshot
+/// sender for the provider to send the response to
+pub type ReqTup<ReqBody, ResBody> = (
+    DiskCacheProviderReq<ReqBody, ResBody>,
+    oneshot::Sender<
+        Result<DiskCacheProviderRes<ReqBody>, DiskCacheProviderError<<ResBody as Body>::Error>>,
+    >,
+);
+
+#[derive(Debug)]
+pub enum DiskCacheProviderReq<ReqBody, ResBody> {
+    Get(http::Request<ReqBody>),
+    Put(DiskCacheProviderCacheKey, http::Response<ResBody>),
+}
+
+#[derive(Debug)]
+pub enum DiskCacheProviderRes<ReqBody> {
+    Get(CacheGetResponse<ReqBody, DiskCacheProviderCacheKey>),
+    Put(http::Response<ConstLruProviderTResBody>),
+}
+
+/// Builder for [`DiskCacheProvider`], mirroring [`crate::const_lru_provider::ConstLruProviderBuilder`]
+/// so the etag builder and oversized-body cap can be combined instead of needing one constructor
+/// per option
+#[derive(Default)]
+pub struct DiskCacheProviderBuilder {
+    etag_builder: Option<Box<dyn EtagBuilder>>,
+    max_cacheable_body_bytes: Option<usize>,
+}
+
+impl DiskCacheProviderBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Computes and compares etags using `etag_builder` instead of the default strong
+    /// base64-blake3 tag
+    pub fn etag_builder(mut self, etag_builder: Box<dyn EtagBuilder>) -> Self {
+        self.etag_builder = Some(etag_builder);
+        self
+    }
+
+    /// Response bodies larger than `max_cacheable_body_bytes` are skipped entirely:
+    /// `on_put_request` streams them back through unmodified, without an ETag, instead of
+    /// caching them
+    pub fn max_cacheable_body_bytes(mut self, max_cacheable_body_bytes: usize) -> Self {
+        self.max_cacheable_body_bytes = Some(max_cacheable_body_bytes);
+        self
+    }
+
+    /// Allocates and creates the [`DiskCacheProvider`] on the heap with the configured options,
+    /// loads any existing index at `index_path` into the hot-cache, and returns the
+    /// [`CacheProvider`] handle to it. See [`DiskCacheProvider::init`] for details
+    pub fn init<ReqBody, ResBody, const CAP: usize, I>(
+        self,
+        req_buffer: usize,
+        index_path: PathBuf,
+    ) -> DiskCacheProviderHandle<ReqBody, ResBody>
+    where
+        ReqBody: Send + 'static,
+        ResBody: Send + Body + 'static,
+        I: PrimInt + Unsigned + Send + 'static,
+        <ResBody as Body>::Data: Send + Buf,
+        <ResBody as Body>::Error: Error + Send + Sync + 'static,
+    {
+        DiskCacheProvider::<ReqBody, ResBody, CAP, I>::init_full(
+            req_buffer,
+            index_path,
+            self.etag_builder
+                .unwrap_or_else(|| Box::new(Base64Blake3EtagBuilder::strong())),
+            self.max_cacheable_body_bytes,
+        )
+    }
+}
+
+/// A disk-backed cache provider.
+///
+/// Mirrors [`crate::const_lru_provider::ConstLruProvider`]'s `CacheProvider`/handle pattern and
+/// reuses its request-validation and oversized-body logic, but backs the `ConstLru` hot-cache
+/// with an append-only index file on disk so entries survive process restarts. Writes to disk
+/// happen in the `run()` actor loop on a blocking-pool thread, and a put's response is returned
+/// only after its index write has finished; `on_get_request` never touches the disk and stays
+/// synchronous.
+///
+/// Keyed by [`DiskCacheProviderCacheKey`], a hash of [`SimpleEtagCacheKey`] -- this is also
+/// exactly what's persisted to the on-disk index, so [`Self::load_index`] can repopulate the
+/// hot-cache directly instead of needing to reconstruct the original key.
+///
+/// Also stores the `SystemTime` of when the cache entry was created, which serves as the
+/// response's last-modified header value.
+pub struct DiskCacheProvider<ReqBody, ResBody: Body, const CAP: usize, I: PrimInt + Unsigned = usize>
+{
+    const_lru: ConstLru<DiskCacheProviderCacheKey, (String, SystemTime), CAP, I>,
+    req_rx: mpsc::Receiver<ReqTup<ReqBody, ResBody>>,
+    index_path: PathBuf,
+    etag_builder: Box<dyn EtagBuilder>,
+    max_cacheable_body_bytes: Option<usize>,
+}
+
+impl<
+        ReqBody: Send + 'static,
+        ResBody: Send + Body + 'static,
+        const CAP: usize,
+        I: PrimInt + Unsigned + Send + 'static,
+    > DiskCacheProvider<ReqBody, ResBody, CAP, I>
+where
+    <ResBody as Body>::Data: Send + Buf,
+    <ResBody as Body>::Error: Error + Send + Sync + 'static,
+{
+    /// Allocates and creates a DiskCacheProvider on the heap, loads any existing index at
+    /// `index_path` into the hot-cache, and returns the [`CacheProvider`] handle to it.
+    ///
+    /// The DiskCacheProvider is dropped once all handles are dropped.
+    ///
+    /// Should be called once on server init
+    ///
+    /// `req_buffer` is the size of the `mpsc::channel` connecting [`DiskCacheProviderHandle`] to
+    /// [`DiskCacheProvider`]
+    pub fn init(req_buffer: usize, index_path: PathBuf) -> DiskCacheProviderHandle<ReqBody, ResBody> {
+        DiskCacheProviderBuilder::new().init::<ReqBody, ResBody, CAP, I>(req_buffer, index_path)
+    }
+
+    fn init_full(
+        req_buffer: usize,
+        index_path: PathBuf,
+        etag_builder: Box<dyn EtagBuilder>,
+        max_cacheable_body_bytes: Option<usize>,
+    ) -> DiskCacheProviderHandle<ReqBody, ResBody> {
+        let (req_tx, req_rx) = mpsc::channel(req_buffer);
+
+        let mut this = Self::boxed(req_rx, index_path, etag_builder, max_cacheable_body_bytes);
+        this.load_index();
+        tokio::spawn(async move { this.run().await });
+
+        DiskCacheProviderHandle {
+            req_tx: PollSender::new(req_tx),
+        }
+    }
+
+    fn boxed(
+        req_rx: mpsc::Receiver<ReqTup<ReqBody, ResBody>>,
+        index_path: PathBuf,
+        etag_builder: Box<dyn EtagBuilder>,
+        max_cacheable_body_bytes: Option<usize>,
+    ) -> Box<Self> {
+        // directly alloc so that a large ConstLru does not trigger stack overflow
+        unsafe {
+            let ptr = alloc(Layout::new::<Self>()) as *mut Self;
+            let const_lru_ptr = addr_of_mut!((*ptr).const_lru);
+            ConstLru::init_at_alloc(const_lru_ptr);
+            let req_rx_ptr = addr_of_mut!((*ptr).req_rx);
+            req_rx_ptr.write(req_rx);
+            addr_of_mut!((*ptr).index_path).write(index_path);
+            addr_of_mut!((*ptr).etag_builder).write(etag_builder);
+            addr_of_mut!((*ptr).max_cacheable_body_bytes).write(max_cacheable_body_bytes);
+            Box::from_raw(ptr)
+        }
+    }
+
+    /// Populates the in-memory hot-cache from the on-disk index, if one exists.
+    ///
+    /// Each line is `key,etag,last_modified_unix_secs`, where `key` is the same
+    /// [`DiskCacheProviderCacheKey`] hash used to look entries up at runtime, so lines can be
+    /// loaded back into `const_lru` directly. Later lines for the same key overwrite earlier
+    /// ones, since the index is append-only and a key may have been `PUT` more than once.
+    fn load_index(&mut self) {
+        let file = match std::fs::File::open(&self.index_path) {
+            Ok(f) => f,
+            Err(_) => return,
+        };
+        for line in std::io::BufReader::new(file).lines().map_while(Result::ok) {
+            let mut parts = line.splitn(3, ',');
+            let (Some(key), Some(etag), Some(secs)) = (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            let (Ok(key), Ok(secs)) = (key.parse::<DiskCacheProviderCacheKey>(), secs.parse::<u64>())
+            else {
+                continue;
+            };
+            // UNIX_EPOCH + Duration panics on overflow; a corrupted or crafted line with an
+            // out-of-range secs should be skipped like any other malformed line, not crash the
+            // whole process on startup
+            let Some(last_modified) = UNIX_EPOCH.checked_add(std::time::Duration::from_secs(secs))
+            else {
+                continue;
+            };
+            // cached etags are later turned back into a HeaderValue with unwrap, so a line
+            // whose etag isn't a valid header value is skipped as malformed too
+            if HeaderValue::from_str(etag).is_err() {
+                continue;
+            }
+            let val = self
+                .const_lru
+                .entry(key)
+                .or_insert_with(|| (etag.to_owned(), last_modified));
+            val.0 = etag.to_owned();
+            val.1 = last_modified;
+        }
+    }
+
+    /// long-running loop
+    async fn run(&mut self) {
+        while let Some((req, resp_tx)) = self.req_rx.recv().await {
+            let res = match req {
+                DiskCacheProviderReq::Get(req) => {
+                    self.on_get_request(req).map(DiskCacheProviderRes::Get)
+                }
+                DiskCacheProviderReq::Put(key, resp) => self
+                    .on_put_request(key, resp)
+                    .await
+                    .map(DiskCacheProviderRes::Put),
+            };
+            // ignore error if resp_rx dropped
+            let _ = resp_tx.send(res);
+        }
+        // exits when all req_tx dropped
+    }
+
+    fn on_get_request(
+        &mut self,
+        req: http::Request<ReqBody>,
+    ) -> Result<
+        CacheGetResponse<ReqBody, DiskCacheProviderCacheKey>,
+        DiskCacheProviderError<ResBody::Error>,
+    > {
+        let key = hash_cache_key(&calc_simple_etag_cache_key(&req));
+        let (cache_etag, last_modified) = match self.const_lru.get(&key) {
+            Some(e) => e,
+            None => {
+                return Ok(CacheGetResponse {
+                    req,
+                    result: CacheGetResponseResult::Miss(key),
+                })
+            }
+        };
+        match validate_cached_entry(
+            req.headers(),
+            cache_etag,
+            *last_modified,
+            Self::set_response_headers,
+        ) {
+            ValidationResult::Hit(header_map) => Ok(CacheGetResponse {
+                req,
+                result: CacheGetResponseResult::Hit(header_map),
+            }),
+            ValidationResult::Miss => Ok(CacheGetResponse {
+                req,
+                result: CacheGetResponseResult::Miss(key),
+            }),
+        }
+    }
+
+    async fn on_put_request(
+        &mut self,
+        key: DiskCacheProviderCacheKey,
+        resp: http::Response<ResBody>,
+    ) -> Result<http::Response<ConstLruProviderTResBody>, DiskCacheProviderError<ResBody::Error>>
+    {
+        let (mut parts, body) = resp.into_parts();
+
+        let mut body = Box::pin(body);
+        let mut buf = bytes::BytesMut::new();
+        while let Some(frame) = body.as_mut().frame().await {
+            let frame = frame.map_err(DiskCacheProviderError::ReadResBody)?;
+            if let Ok(mut data) = frame.into_data() {
+                bytes::BufMut::put(&mut buf, &mut data);
+                if let Some(max_cacheable_body_bytes) = self.max_cacheable_body_bytes {
+                    if buf.len() > max_cacheable_body_bytes {
+                        // Give up on caching and computing an etag for this entry right away:
+                        // stream what's already been buffered followed by the rest of the body,
+                        // same as ConstLruProvider::on_put_request
+                        let tres_body =
+                            ConstLruProviderTResBody::from_prefixed_body(buf.freeze(), body);
+                        return Ok(http::Response::from_parts(parts, tres_body));
+                    }
+                }
+            }
+        }
+        let body_bytes = buf.freeze();
+
+        let etag = self.etag_builder.build_etag(&body_bytes);
+        // unwrap-safety: base64/hex alphabets are always valid ascii
+        let etag_str = etag.to_str().unwrap();
+
+        let curr_val = self
+            .const_lru
+            .entry(key)
+            .or_insert_with(|| (etag_str.to_owned(), SystemTime::now()));
+
+        // don't modify if cached etag is already the same
+        if curr_val.0 != etag_str {
+            curr_val.0 = etag_str.to_owned();
+            curr_val.1 = SystemTime::now();
+        }
+
+        let last_modified = curr_val.1;
+        Self::set_response_headers(&mut parts.headers, etag, last_modified);
+
+        self.persist_entry(key, etag_str, last_modified).await;
+
+        Ok(http::Response::from_parts(parts, body_bytes.into()))
+    }
+
+    /// Appends the entry to the on-disk index on a blocking-pool thread. The write is awaited, so
+    /// the put's response waits for it and blocking tasks don't pile up unbounded under load. A
+    /// failed disk write does not fail the response -- it only means the entry won't survive the
+    /// next restart -- but is logged rather than silently discarded.
+    async fn persist_entry(&self, key: DiskCacheProviderCacheKey, etag_str: &str, last_modified: SystemTime) {
+        let secs = last_modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let index_path = self.index_path.clone();
+        let line = format!("{key},{etag_str},{secs}\n");
+        let write_result = tokio::task::spawn_blocking(move || {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(index_path)?;
+            file.write_all(line.as_bytes())
+        })
+        .await;
+        match write_result {
+            Ok(Ok(())) => {}
+            Ok(Err(io_err)) => {
+                eprintln!("disk cache index write for key {key} failed: {io_err}");
+            }
+            Err(join_err) => {
+                eprintln!("disk cache index write for key {key} panicked: {join_err}");
+            }
+        }
+    }
+
+    fn set_response_headers(
+        headers_mut: &mut HeaderMap,
+        etag_val: HeaderValue,
+        last_modified_val: SystemTime,
+    ) {
+        headers_mut.append(ETAG, etag_val);
+        headers_mut.append(
+            CACHE_CONTROL,
+            HeaderValue::from_static("max-age=604800,stale-while-revalidate=86400"),
+        );
+        let last_modified_val = OffsetDateTime::from(last_modified_val)
+            .format(&Rfc2822)
+            .unwrap();
+        headers_mut.append(
+            LAST_MODIFIED,
+            HeaderValue::from_str(&last_modified_val).unwrap(),
+        );
+        SimpleEtagCacheKey::set_response_headers(headers_mut);
+    }
+}
+
+// SERVICE HANDLE
+
+pub struct DiskCacheProviderHandle<ReqBody, ResBody: Body> {
+    req_tx: PollSender<ReqTup<ReqBody, ResBody>>,
+}
+
+impl<ReqBody, ResBody: Body> Clone for DiskCacheProviderHandle<ReqBody, ResBody> {
+    fn clone(&self) -> Self {
+        Self {
+            req_tx: self.req_tx.clone(),
+        }
+    }
+}
+
+impl<ReqBody: Send, ResBody: Body + Send> CacheProvider<ReqBody, ResBody>
+    for DiskCacheProviderHandle<ReqBody, ResBody>
+where
+    ResBody::Error: Send,
+{
+    type Key = DiskCacheProviderCacheKey;
+    type TResBody = ConstLruProviderTResBody;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+
+    fn temp_index_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "tower-etag-cache-test-{name}-{}-{}.index",
+            std::process::id(),
+            name.len()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn test_provider(
+        index_path: PathBuf,
+    ) -> Box<DiskCacheProvider<http::Request<()>, Full<bytes::Bytes>, 4, usize>> {
+        let (_req_tx, req_rx) = mpsc::channel(1);
+        DiskCacheProvider::boxed(
+            req_rx,
+            index_path,
+            Box::new(Base64Blake3EtagBuilder::strong()),
+            None,
+        )
+    }
+
+    fn get_request(path: &str) -> http::Request<()> {
+        http::Request::builder().uri(path).body(()).unwrap()
+    }
+
+    fn put_response(body: &'static [u8]) -> http::Response<Full<bytes::Bytes>> {
+        http::Response::new(Full::new(bytes::Bytes::from_static(body)))
+    }
+
+    #[test]
+    fn load_index_skips_malformed_and_overflowing_lines() {
+        let index_path = temp_index_path("malformed");
+        std::fs::write(
+            &index_path,
+            "42,etag-ok,1700000000\n\
+             notenoughfields\n\
+             badkey,etag-bad-key,1\n\
+             7,etag-overflow,99999999999999999999\n",
+        )
+        .unwrap();
+
+        let mut provider = test_provider(index_path.clone());
+        // must not panic on the out-of-range `secs` line
+        provider.load_index();
+
+        assert_eq!(provider.const_lru.len(), 1);
+        assert!(provider.const_lru.get(&42).is_some());
+        assert!(provider.const_lru.get(&7).is_none());
+
+        std::fs::remove_file(&index_path).ok();
+    }
+
+    #[tokio::test]
+    async fn persist_then_reload_into_fresh_provider_round_trips() {
+        let index_path = temp_index_path("roundtrip");
+        let mut provider = test_provider(index_path.clone());
+
+        let req = get_request("/a");
+        let key = hash_cache_key(&calc_simple_etag_cache_key(&req));
+        provider
+            .on_put_request(key, put_response(b"hello"))
+            .await
+            .unwrap();
+
+        // a fresh provider over the same index file, simulating a process restart
+        let mut reloaded = test_provider(index_path.clone());
+        reloaded.load_index();
+
+        let (etag, _) = reloaded
+            .const_lru
+            .get(&key)
+            .expect("entry should survive reload");
+        assert!(!etag.is_empty());
+
+        std::fs::remove_file(&index_path).ok();
+    }
+}
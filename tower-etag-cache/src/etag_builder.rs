@@ -0,0 +1,141 @@
+//! Pluggable ETag computation: strong/weak validators over base64 or hex digest encodings,
+//! plus entity-tag comparison per RFC 7232 section 2.3.2
+
+use data_encoding::{BASE64, HEXLOWER};
+use http::HeaderValue;
+
+/// Produces the `ETag` `HeaderValue` for a response body.
+///
+/// Held by a cache provider and invoked from `on_put_request` so the emitted (and later
+/// compared) tag is configurable. See [`Base64Blake3EtagBuilder`] and [`HexBlake3EtagBuilder`]
+/// for the built-in implementations.
+pub trait EtagBuilder: Send + Sync {
+    fn build_etag(&self, body: &[u8]) -> HeaderValue;
+}
+
+fn blake3_digest(body: &[u8]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(body);
+    *hasher.finalize().as_bytes()
+}
+
+fn format_etag(weak: bool, encoded: &str) -> HeaderValue {
+    let val = if weak {
+        format!(r#"W/"{encoded}""#)
+    } else {
+        format!(r#""{encoded}""#)
+    };
+    // unwrap-safety: base64/hex alphabets are always valid ascii
+    HeaderValue::from_str(&val).unwrap()
+}
+
+/// Blake3 digest, base64-encoded. Strong by default; this is the default [`EtagBuilder`] used by
+/// both [`crate::const_lru_provider::ConstLruProvider`] and
+/// [`crate::disk_cache_provider::DiskCacheProvider`]
+#[derive(Debug, Clone, Copy)]
+pub struct Base64Blake3EtagBuilder {
+    pub weak: bool,
+}
+
+impl Base64Blake3EtagBuilder {
+    pub const fn strong() -> Self {
+        Self { weak: false }
+    }
+
+    pub const fn weak() -> Self {
+        Self { weak: true }
+    }
+}
+
+impl EtagBuilder for Base64Blake3EtagBuilder {
+    fn build_etag(&self, body: &[u8]) -> HeaderValue {
+        format_etag(self.weak, &BASE64.encode(&blake3_digest(body)))
+    }
+}
+
+/// Blake3 digest, lowercase-hex-encoded, for deployments that need to match ETags generated
+/// elsewhere with a hex digest
+#[derive(Debug, Clone, Copy)]
+pub struct HexBlake3EtagBuilder {
+    pub weak: bool,
+}
+
+impl HexBlake3EtagBuilder {
+    pub const fn strong() -> Self {
+        Self { weak: false }
+    }
+
+    pub const fn weak() -> Self {
+        Self { weak: true }
+    }
+}
+
+impl EtagBuilder for HexBlake3EtagBuilder {
+    fn build_etag(&self, body: &[u8]) -> HeaderValue {
+        format_etag(self.weak, &HEXLOWER.encode(&blake3_digest(body)))
+    }
+}
+
+/// Entity-tag comparison per RFC 7232 section 2.3.2: strips the optional weak-validator `W/` prefix
+/// before comparing, and treats `*` as matching any cached etag
+pub fn etag_matches(request_etag: &str, cached_etag: &str) -> bool {
+    if request_etag == "*" {
+        return true;
+    }
+    strip_weak_prefix(request_etag) == strip_weak_prefix(cached_etag)
+}
+
+fn strip_weak_prefix(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_weak_prefix_strips_only_leading_weak_marker() {
+        assert_eq!(strip_weak_prefix(r#"W/"abc""#), r#""abc""#);
+        assert_eq!(strip_weak_prefix(r#""abc""#), r#""abc""#);
+    }
+
+    #[test]
+    fn etag_matches_wildcard_matches_anything() {
+        assert!(etag_matches("*", r#""abc""#));
+        assert!(etag_matches("*", r#"W/"abc""#));
+    }
+
+    #[test]
+    fn etag_matches_strong_to_strong() {
+        assert!(etag_matches(r#""abc""#, r#""abc""#));
+        assert!(!etag_matches(r#""abc""#, r#""def""#));
+    }
+
+    #[test]
+    fn etag_matches_weak_to_weak() {
+        assert!(etag_matches(r#"W/"abc""#, r#"W/"abc""#));
+    }
+
+    #[test]
+    fn etag_matches_weak_to_strong_same_opaque_tag() {
+        // Per RFC 7232 2.3.2, comparing the opaque tag ignores the weak/strong marker
+        assert!(etag_matches(r#"W/"abc""#, r#""abc""#));
+        assert!(etag_matches(r#""abc""#, r#"W/"abc""#));
+    }
+
+    #[test]
+    fn base64_builder_formats_strong_and_weak() {
+        let strong = Base64Blake3EtagBuilder::strong().build_etag(b"hello");
+        let weak = Base64Blake3EtagBuilder::weak().build_etag(b"hello");
+        assert!(!strong.to_str().unwrap().starts_with("W/"));
+        assert!(weak.to_str().unwrap().starts_with("W/"));
+        assert!(etag_matches(strong.to_str().unwrap(), weak.to_str().unwrap()));
+    }
+
+    #[test]
+    fn hex_builder_produces_lowercase_hex() {
+        let etag = HexBlake3EtagBuilder::strong().build_etag(b"hello");
+        let s = etag.to_str().unwrap();
+        assert!(s.trim_matches('"').chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+    }
+}